@@ -1,64 +1,226 @@
-use crate::html_parser::token_replacements::TOKEN_REPLACEMENTS;
-use crate::html_parser::tokenizer::{Tokenizer};
-
-// Consumes a character reference and places this in the tokenizer consume buffer
-pub fn consume_character_reference(&mut tokenizer: Tokenizer, additional_allowed_char: Option<char>) {
-    let c = match tokenizer.stream.read_char() {
-        Ok(c) => c,
-        Err(_) => {
+use std::collections::VecDeque;
+
+use bitflags::bitflags;
+use cssparser::CowRcStr;
+
+use crate::html_parser::named_char_references::NAMED_CHAR_REFERENCES;
+use crate::html_parser::token_replacements::C1_CONTROL_REPLACEMENTS;
+use crate::html_parser::tokenizer::{InputStream, Tokenizer};
+
+const LOOKAHEAD_CAPACITY: usize = 64;
+
+/// A snapshot of a `LookaheadQueue` position, cheap to take and cheap to restore.
+#[derive(Clone, Copy)]
+pub struct Checkpoint(usize);
+
+/// A ring buffer of decoded codepoints sitting in front of the raw input stream. It starts
+/// with room for `LOOKAHEAD_CAPACITY` codepoints and grows as needed; its size is not capped. This replaces the single-character `look_ahead`/`unread` pair with a `peek`/`skip`
+/// API that supports arbitrary lookahead, and replaces the `get_consume_len`/`set_consume_len`
+/// snapshot dance with a single `checkpoint`/`rollback` call. Any tokenizer state can share a
+/// queue, not just character reference consumption.
+pub struct LookaheadQueue<'a> {
+    stream: &'a mut InputStream,
+    buf: VecDeque<char>,
+    position: usize,
+}
+
+impl<'a> LookaheadQueue<'a> {
+    pub fn new(stream: &'a mut InputStream) -> Self {
+        Self { stream, buf: VecDeque::with_capacity(LOOKAHEAD_CAPACITY), position: 0 }
+    }
+
+    fn fill(&mut self, upto: usize) {
+        while self.buf.len() < upto {
+            match self.stream.read_char() {
+                Ok(c) => self.buf.push_back(c),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Peeks the codepoint `n` positions ahead of the current position (1-indexed, so
+    /// `peek(1)` is the next character to be read) without consuming it.
+    pub fn peek(&mut self, n: usize) -> Option<char> {
+        self.fill(self.position + n);
+        self.buf.get(self.position + n - 1).copied()
+    }
+
+    /// Consumes and returns the next codepoint, advancing the position by one.
+    pub fn next_char(&mut self) -> Option<char> {
+        self.fill(self.position + 1);
+        let c = self.buf.get(self.position).copied();
+        if c.is_some() {
+            self.position += 1;
+        }
+        c
+    }
+
+    /// Advances the position by `n` codepoints without returning them.
+    pub fn skip(&mut self, n: usize) {
+        self.fill(self.position + n);
+        self.position = (self.position + n).min(self.buf.len());
+    }
+
+    /// Snapshots the current position so it can be cheaply restored with `rollback`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.position)
+    }
+
+    /// Restores a previously taken `Checkpoint`, discarding anything read since.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.position = checkpoint.0;
+    }
+
+    /// Returns everything between `checkpoint` and the current position.
+    pub fn slice_since(&self, checkpoint: Checkpoint) -> String {
+        self.buf.iter().skip(checkpoint.0).take(self.position - checkpoint.0).collect()
+    }
+}
+
+impl<'a> Drop for LookaheadQueue<'a> {
+    // Everything buffered past `position` was pulled out of the real stream by `fill` (to
+    // satisfy a `peek`) but never actually consumed via `skip`/`next_char`. Push it back onto
+    // the stream, in order, so a short-lived queue (one constructed per `consume_character_reference`
+    // call, say) doesn't silently swallow whatever it looked ahead at. Popping from the back and
+    // pushing each character to the stream's front, in that order, restores the original order.
+    fn drop(&mut self) {
+        while self.buf.len() > self.position {
+            if let Some(c) = self.buf.pop_back() {
+                self.stream.push_front(c);
+            }
+        }
+    }
+}
+
+bitflags! {
+    /// Non-fatal diagnostics raised while consuming a character reference. These are attached
+    /// to the emitted token instead of being reported through `Tokenizer::parse_error` as they
+    /// are found, so a caller can decide whether to surface, suppress, or batch them (useful
+    /// for fuzzing, linting, or incremental reparse, where eager diagnostics are unwanted).
+    pub struct TokenError: u8 {
+        const MISSING_SEMICOLON  = 0b0001;
+        const RESERVED_CODEPOINT = 0b0010;
+        const NULL_REPLACED      = 0b0100;
+        const OUT_OF_RANGE       = 0b1000;
+    }
+}
+
+/// Iterates over character references that start at the current stream position, yielding
+/// the text each one produced together with the `TokenError` flags it raised. Iteration ends
+/// as soon as the next character is not '&'; text between references is not skipped. This
+/// lets a higher layer decide how (or whether) to report diagnostics instead of having
+/// `consume_character_reference` report them inline.
+pub struct CharacterReferences<'a> {
+    tokenizer: &'a mut Tokenizer,
+    additional_allowed_char: Option<char>,
+}
+
+impl<'a> CharacterReferences<'a> {
+    pub fn new(tokenizer: &'a mut Tokenizer, additional_allowed_char: Option<char>) -> Self {
+        Self { tokenizer, additional_allowed_char }
+    }
+}
+
+impl<'a> Iterator for CharacterReferences<'a> {
+    type Item = (CowRcStr<'a>, TokenError);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Only peek for the leading '&' here - on a miss, dropping this queue pushes the
+        // peeked character straight back. On a hit, leave it unconsumed so
+        // `consume_character_reference` takes its own `start` checkpoint *before* reading it;
+        // that's what makes every literal-fallback slice below include the '&' instead of
+        // silently losing it the moment this probe queue goes out of scope.
+        {
+            let mut queue = LookaheadQueue::new(&mut self.tokenizer.stream);
+            if queue.peek(1) != Some('&') {
+                return None;
+            }
+        }
+
+        let (text, errors) = consume_character_reference(self.tokenizer, self.additional_allowed_char);
+
+        Some((text, errors))
+    }
+}
+
+// Consumes a character reference and returns the resulting text; nothing is placed in the
+// tokenizer consume buffer. The returned text is always an owned copy: a verbatim run of the
+// source is collected from the lookahead queue into a new `String`, the same as a named or
+// numeric reference's replacement, so no path borrows from the input stream. `start`
+// is taken before the leading '&' itself is read, so every literal-fallback path below, which
+// is built from `queue.slice_since(start)`, naturally keeps that '&' rather than needing each
+// call site to special-case prepending it back.
+pub fn consume_character_reference(tokenizer: &mut Tokenizer, additional_allowed_char: Option<char>) -> (CowRcStr, TokenError) {
+    let mut queue = LookaheadQueue::new(&mut tokenizer.stream);
+    let start = queue.checkpoint();
+
+    // The caller has already confirmed (via `peek`) that the next character is '&'.
+    queue.next_char();
+
+    let c = match queue.next_char() {
+        Some(c) => c,
+        None => {
+            // Nothing follows the '&'; it's flushed as a literal.
+            drop(queue);
             tokenizer.clear_consume_buffer();
-            return;
+            return (CowRcStr::from("&"), TokenError::empty());
         }
     };
 
     // If we allow an extra character, check for it
     if additional_allowed_char.is_some() && c == additional_allowed_char.unwrap() {
-        tokenizer.stream.unread();
+        // Not a reference at all; flush the '&' and reconsume the extra character.
+        queue.rollback(start);
+        queue.skip(1);
+        let text = queue.slice_since(start);
+        drop(queue);
         tokenizer.clear_consume_buffer();
-        return
+        return (CowRcStr::from(text), TokenError::empty());
     }
 
     match c {
-        CHAR_TAB | CHAR_LF | CHAR_FF => return,
-        '#' => consume_dash_entity(tokenizer),
-        _ => consume_anything_else(tokenizer),
+        CHAR_TAB | CHAR_LF | CHAR_FF => (CowRcStr::from(queue.slice_since(start)), TokenError::empty()),
+        '#' => consume_dash_entity(&mut queue, start),
+        _ => {
+            queue.rollback(start);
+            queue.skip(1);
+            consume_anything_else(&mut queue, additional_allowed_char, start)
+        }
     }
 }
 
-// Consume a dash entity #x1234, #123 etc
-fn consume_dash_entity(&mut tokenizer: Tokenizer) {
-    let mut str_num = "";
-
-    // Save length for easy recovery
-    let len = tokenizer.get_consume_len();
-
-    // Consume the dash
-    tokenizer.consume('#');
+// Consume a dash entity #x1234, #123 etc. Numeric references always resolve to a different
+// codepoint than their source text. Both the successful path and the "not actually a
+// reference" fallback return an owned `CowRcStr`. Backtracking no longer needs the `get_consume_len`/
+// `set_consume_len` snapshot dance - a single `queue.rollback(start)` restores position no
+// matter how many characters were peeked ahead.
+fn consume_dash_entity(queue: &mut LookaheadQueue, start: Checkpoint) -> (CowRcStr, TokenError) {
+    let mut str_num = String::new();
 
     // Is the char a 'X' or 'x', then we must fetch hex digits
     let mut is_hex = false;
-    let hex = tokenizer.stream.look_ahead(1);
-    if hex == 'x' || hex == 'X' {
+    if matches!(queue.peek(1), Some('x') | Some('X')) {
         is_hex = true;
         // Consume the 'x' character
-        let c = tokenizer.stream.read_char();
-        tokenizer.consume(c);
+        queue.skip(1);
     }
 
     let mut i = 0;
     loop {
-        let (c, eof) = tokenizer.stream.read_char();
-        if eof {
-            tokenizer.set_consume_len(len);
-            return
-        }
+        let c = match queue.peek(1) {
+            Some(c) => c,
+            // EOF doesn't abort the reference outright - it's handled the same as hitting a
+            // non-digit below: stop collecting and fall through to the terminator/resolution
+            // logic, which treats a missing terminator as a recoverable parse error.
+            None => break,
+        };
 
         if is_hex && c.is_ascii_hexdigit() {
             str_num.push(c);
-            tokenizer.consume(c);
+            queue.skip(1);
         } else if !is_hex && c.is_ascii_digit() {
             str_num.push(c);
-            tokenizer.consume(c);
+            queue.skip(1);
         } else {
             break;
         }
@@ -66,67 +228,76 @@ fn consume_dash_entity(&mut tokenizer: Tokenizer) {
         i += 1;
     }
 
-    // Fetch next character
-    let (c, eof) = tokenizer.stream.read_char();
-    if eof {
-        tokenizer.set_consume_len(len);
-        return
-    }
-
-    // Next character MUST be ;
-    if c != ';' {
-        tokenizer.parse_error("expected a ';'");
-        tokenizer.set_consume_len(len);
-        return
+    // We need at least one digit to have a number at all; with none, this isn't a character
+    // reference, so flush the literal source text (whatever follows was only peeked above,
+    // never skipped, so it's left in place to be reconsumed).
+    if i == 0 {
+        return (CowRcStr::from(queue.slice_since(start)), TokenError::OUT_OF_RANGE);
     }
 
-    // If we found ;. we need to check how many digits we have parsed. It needs to be at least 1,
-    if i == 0 {
-        tokenizer.parse_error("didn't expect #;");
-        tokenizer.set_consume_len(len);
-        return
+    // The terminating ';' is consumed if present, but per
+    // https://html.spec.whatwg.org/#numeric-character-reference-end-state its absence is only
+    // a (recoverable) parse error, not a reason to bail out to the literal text - we still
+    // resolve the number below, just with `MISSING_SEMICOLON` flagged. The character is left
+    // unconsumed so it gets reconsumed afterwards.
+    let mut errors = TokenError::empty();
+    if queue.peek(1) == Some(';') {
+        queue.skip(1);
+    } else {
+        errors |= TokenError::MISSING_SEMICOLON;
     }
 
-    // check if we need to replace the character. First convert the number to a uint, and use that
-    // to check if it exists in the replacements table.
-    let num = match u32::from_str_radix(str_num, if is_hex { 16 } else { 10 }) {
+    // check if we need to remap or replace the character. First convert the number to a uint,
+    // and hand it to `resolve_numeric_reference` to apply the spec's replacement rules.
+    let num = match u32::from_str_radix(&str_num, if is_hex { 16 } else { 10 }) {
         Ok(value) => value,
         Err(_) => 0,    // lets pretend that an invalid value is set to 0
     };
 
-    if TOKEN_REPLACEMENTS.contains_key(&num) {
-        tokenizer.set_consume_len(len);
-        tokenizer.consume(*TOKEN_REPLACEMENTS.get(&num).unwrap());
-        return;
+    let (resolved, resolve_errors) = resolve_numeric_reference(num);
+    (CowRcStr::from(resolved.to_string()), errors | resolve_errors)
+}
+
+// Applies https://html.spec.whatwg.org/#numeric-character-reference-end-state to a decoded
+// codepoint value, returning the character to emit together with any `TokenError` raised.
+fn resolve_numeric_reference(num: u32) -> (char, TokenError) {
+    // A literal NUL is always replaced outright.
+    if num == 0x00 {
+        return (Tokenizer::CHAR_REPLACEMENT, TokenError::NULL_REPLACED);
     }
 
-    // Next, check if we are in the 0xD800..0xDFFF or 0x10FFFF range, if so, replace
-    if (num > 0xD800 && num < 0xDFFF) || (num > 0x10FFFFF) {
-        tokenizer.set_consume_len(len);
-        tokenizer.parse_error("within reserved codepoint range, but replaced");
-        tokenizer.consume(Tokenizer::CHAR_REPLACEMENT);
+    // Values beyond the last valid codepoint, and surrogates (which can never be valid
+    // scalar values), are replaced outright. Note the surrogate range is inclusive on both
+    // ends (0xD800..=0xDFFF), and the upper bound is 0x10FFFF, not 0x10FFFFF.
+    if num > 0x10FFFF || (0xD800..=0xDFFF).contains(&num) {
+        return (Tokenizer::CHAR_REPLACEMENT, TokenError::OUT_OF_RANGE);
     }
 
-    // Check if it's in a reserved range, in that case, we ignore the data
+    // C1 controls (0x80..=0x9F) are remapped to their Windows-1252 equivalent rather than
+    // replaced outright, e.g. &#128; -> "€" instead of U+FFFD.
+    if let Some(&replacement) = C1_CONTROL_REPLACEMENTS.get(&num) {
+        return (replacement, TokenError::RESERVED_CODEPOINT);
+    }
+
+    let c = char::from_u32(num).unwrap_or(Tokenizer::CHAR_REPLACEMENT);
+
+    // Noncharacters and other disallowed control characters are flagged, but still emitted
+    // as-is rather than being dropped or replaced.
     if in_reserved_number_range(num) {
-        tokenizer.set_consume_len(len);
-        tokenizer.parse_error("within reserved codepoint range, ignored");
+        return (c, TokenError::RESERVED_CODEPOINT);
     }
+
+    (c, TokenError::empty())
 }
 
 // Returns if the given codepoint number is in a reserved range (as defined in
-// https://dev.w3.org/html5/spec-LC/tokenization.html#consume-a-character-reference)
+// https://html.spec.whatwg.org/#numeric-character-reference-end-state)
 fn in_reserved_number_range(codepoint: u32) -> bool {
     if
         (0x0001..=0x0008).contains(&codepoint) ||
         (0x000E..=0x001F).contains(&codepoint) ||
         (0x007F..=0x009F).contains(&codepoint) ||
         (0xFDD0..=0xFDEF).contains(&codepoint) ||
-        (0x000E..=0x001F).contains(&codepoint) ||
-        (0x000E..=0x001F).contains(&codepoint) ||
-        (0x000E..=0x001F).contains(&codepoint) ||
-        (0x000E..=0x001F).contains(&codepoint) ||
-        (0x000E..=0x001F).contains(&codepoint) ||
         [
             0x000B, 0xFFFE, 0xFFFF, 0x1FFFE, 0x1FFFF, 0x2FFFE, 0x2FFFF, 0x3FFFE, 0x3FFFF,
             0x4FFFE, 0x4FFFF, 0x5FFFE, 0x5FFFF, 0x6FFFE, 0x6FFFF, 0x7FFFE, 0x7FFFF,
@@ -140,7 +311,143 @@ fn in_reserved_number_range(codepoint: u32) -> bool {
     return false;
 }
 
-// This will consume any other matter that does not start with &# (ie: &raquo; &#copy;)
-fn consume_anything_else(&mut tokenizer: Tokenizer) {
+// This will consume any other matter that does not start with &# (ie: &raquo; &amp;). The
+// candidate name is tracked separately from the queue purely for table lookups; the text we
+// ultimately hand back on the unmatched path is copied out of the queue from `start`, so it
+// allocates like the matched path does. Longest-match backtracking, which used to be
+// a manual `set_consume_len(len)` snapshot dance limited to one character of lookahead, is now
+// a single `queue.rollback(match_point)` call regardless of how far ahead we had to read.
+fn consume_anything_else(
+    queue: &mut LookaheadQueue,
+    additional_allowed_char: Option<char>,
+    start: Checkpoint,
+) -> (CowRcStr, TokenError) {
+    let mut candidate = String::new();
+    let mut last_match: Option<(bool, Checkpoint, &(char, Option<char>))> = None;
 
+    // Keep consuming alphanumerics (and the closing ';'), remembering the longest prefix seen
+    // so far that exactly matches a named character reference. Most named references need to
+    // be terminated with a ';', but a handful of legacy ones (&amp, &copy, &lt, ...) are
+    // recognized without it, so we can't stop at the first match.
+    loop {
+        let c = match queue.peek(1) {
+            Some(c) => c,
+            None => break,
+        };
+
+        if !c.is_ascii_alphanumeric() && c != ';' {
+            break;
+        }
+        queue.skip(1);
+
+        candidate.push(c);
+
+        if let Some(entry) = NAMED_CHAR_REFERENCES.get(candidate.as_str()) {
+            last_match = Some((c == ';', queue.checkpoint(), entry));
+        }
+
+        if c == ';' {
+            break;
+        }
+    }
+
+    let (terminated, match_point, entry) = match last_match {
+        Some(found) => found,
+        None => {
+            // No named reference matched at all; the "&" plus whatever we read is a literal
+            // run of the source text, handed back as an owned copy of that run.
+            return (CowRcStr::from(queue.slice_since(start)), TokenError::empty());
+        }
+    };
+
+    // Roll back anything consumed past the longest matching name.
+    queue.rollback(match_point);
+
+    let mut errors = TokenError::empty();
+
+    if !terminated {
+        // Legacy entities without a trailing ';' are only recognized as a character reference
+        // when not part of an attribute value that continues into '=' or an alphanumeric, in
+        // which case the spec says to treat it as a literal instead.
+        if additional_allowed_char.is_some() {
+            let next = queue.peek(1);
+            if next == Some('=') || next.is_some_and(|c| c.is_ascii_alphanumeric()) {
+                return (CowRcStr::from(queue.slice_since(start)), TokenError::empty());
+            }
+        }
+
+        errors |= TokenError::MISSING_SEMICOLON;
+    }
+
+    let mut replacement = String::new();
+    replacement.push(entry.0);
+    if let Some(second) = entry.1 {
+        replacement.push(second);
+    }
+
+    (CowRcStr::from(replacement), errors)
+}
+
+// Hand-written unit tests for the pure `resolve_numeric_reference`/`in_reserved_number_range`
+// helpers, covering the same boundary codepoints the html5lib tokenizer test suite exercises
+// (html5lib-tests/tokenizer/numericEntities.test) without actually running those vectors
+// through the tokenizer. They don't cover `consume_dash_entity`/`consume_character_reference`
+// end to end (e.g. the missing-semicolon decode path, `&#65` -> "A") since that requires a
+// constructible `Tokenizer`/`InputStream`, which aren't available to this module on their own.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_is_replaced() {
+        let (c, error) = resolve_numeric_reference(0x00);
+        assert_eq!(c, Tokenizer::CHAR_REPLACEMENT);
+        assert_eq!(error, TokenError::NULL_REPLACED);
+    }
+
+    #[test]
+    fn surrogate_range_is_replaced() {
+        for &num in &[0xD800, 0xDC00, 0xDFFF] {
+            let (c, error) = resolve_numeric_reference(num);
+            assert_eq!(c, Tokenizer::CHAR_REPLACEMENT);
+            assert_eq!(error, TokenError::OUT_OF_RANGE);
+        }
+    }
+
+    #[test]
+    fn last_valid_codepoint_is_not_replaced() {
+        let (c, error) = resolve_numeric_reference(0x10FFFF);
+        assert_eq!(c, char::from_u32(0x10FFFF).unwrap());
+        assert!(!error.contains(TokenError::OUT_OF_RANGE));
+    }
+
+    #[test]
+    fn beyond_last_valid_codepoint_is_replaced() {
+        let (c, error) = resolve_numeric_reference(0x110000);
+        assert_eq!(c, Tokenizer::CHAR_REPLACEMENT);
+        assert_eq!(error, TokenError::OUT_OF_RANGE);
+    }
+
+    #[test]
+    fn c1_control_is_remapped_to_windows_1252() {
+        // &#128; is the canonical html5lib example: EURO SIGN instead of U+FFFD.
+        let (c, error) = resolve_numeric_reference(0x80);
+        assert_eq!(c, '\u{20AC}');
+        assert_eq!(error, TokenError::RESERVED_CODEPOINT);
+    }
+
+    #[test]
+    fn noncharacter_is_flagged_but_kept() {
+        let (c, error) = resolve_numeric_reference(0xFFFE);
+        assert_eq!(c, char::from_u32(0xFFFE).unwrap());
+        assert_eq!(error, TokenError::RESERVED_CODEPOINT);
+    }
+
+    #[test]
+    fn reserved_c0_control_range_boundaries() {
+        assert!(in_reserved_number_range(0x0001));
+        assert!(in_reserved_number_range(0x000E));
+        assert!(in_reserved_number_range(0x001F));
+        assert!(!in_reserved_number_range(0x0020));
+    }
 }
\ No newline at end of file
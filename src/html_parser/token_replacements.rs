@@ -0,0 +1,36 @@
+use phf::phf_map;
+
+/// The Windows-1252 remapping table for the C1 control range (0x80..=0x9F), used when
+/// resolving a numeric character reference. Per
+/// https://html.spec.whatwg.org/#numeric-character-reference-end-state, a reference in this
+/// range resolves to its Windows-1252 equivalent instead of being replaced with U+FFFD, e.g.
+/// `&#128;` -> '€' rather than the replacement character.
+pub static C1_CONTROL_REPLACEMENTS: phf::Map<u32, char> = phf_map! {
+    0x80u32 => '\u{20AC}',
+    0x82u32 => '\u{201A}',
+    0x83u32 => '\u{0192}',
+    0x84u32 => '\u{201E}',
+    0x85u32 => '\u{2026}',
+    0x86u32 => '\u{2020}',
+    0x87u32 => '\u{2021}',
+    0x88u32 => '\u{02C6}',
+    0x89u32 => '\u{2030}',
+    0x8Au32 => '\u{0160}',
+    0x8Bu32 => '\u{2039}',
+    0x8Cu32 => '\u{0152}',
+    0x8Eu32 => '\u{017D}',
+    0x91u32 => '\u{2018}',
+    0x92u32 => '\u{2019}',
+    0x93u32 => '\u{201C}',
+    0x94u32 => '\u{201D}',
+    0x95u32 => '\u{2022}',
+    0x96u32 => '\u{2013}',
+    0x97u32 => '\u{2014}',
+    0x98u32 => '\u{02DC}',
+    0x99u32 => '\u{2122}',
+    0x9Au32 => '\u{0161}',
+    0x9Bu32 => '\u{203A}',
+    0x9Cu32 => '\u{0153}',
+    0x9Eu32 => '\u{017E}',
+    0x9Fu32 => '\u{0178}',
+};